@@ -1,210 +1,664 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
 
+use petgraph::algo::is_cyclic_directed;
 use petgraph::data::DataMapMut;
 use petgraph::graph::{EdgeIndex, NodeIndex};
 use petgraph::stable_graph::StableGraph;
 use petgraph::visit::{
-    Data, DfsPostOrder, GraphBase, IntoNeighborsDirected, NodeIndexable, Reversed, Visitable,
+    Data, DfsPostOrder, EdgeRef, GraphBase, IntoEdgesDirected, IntoNeighbors, IntoNodeIdentifiers,
+    NodeIndexable, Reversed, Visitable,
 };
 use petgraph::Incoming;
 
-pub struct NodeData<T> {
-    pub buffer: i32,
+/// A block of `N` audio samples across one or more channels, processed together
+/// each call to `next_block`.
+#[derive(Debug, Clone)]
+pub struct Buffer<const N: usize> {
+    channels: Vec<[f32; N]>,
+}
+
+impl<const N: usize> Buffer<N> {
+    /// A silent buffer with `channels` channels.
+    pub fn new(channels: usize) -> Self {
+        Buffer {
+            channels: vec![[0.0; N]; channels],
+        }
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn channel(&self, index: usize) -> &[f32; N] {
+        &self.channels[index]
+    }
+
+    pub fn channel_mut(&mut self, index: usize) -> &mut [f32; N] {
+        &mut self.channels[index]
+    }
+
+    /// Adds `other` into `self`, sample by sample, on every channel.
+    fn add_assign(&mut self, other: &Buffer<N>) {
+        for ch in 0..self.channel_count() {
+            for (o, i) in self
+                .channel_mut(ch)
+                .iter_mut()
+                .zip(other.channel(ch).iter())
+            {
+                *o += i;
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for Buffer<N> {
+    /// A single silent (all-zero) channel.
+    fn default() -> Self {
+        Buffer::new(1)
+    }
+}
+
+impl<const N: usize> From<[f32; N]> for Buffer<N> {
+    /// A single-channel buffer holding `samples`.
+    fn from(samples: [f32; N]) -> Self {
+        Buffer {
+            channels: vec![samples],
+        }
+    }
+}
+
+/// How a node's inputs should be mixed down to (or up to) its own channel count,
+/// mirroring `channelCountMode`/`channelInterpretation` in the Web Audio API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelInterpretation {
+    /// Mix using speaker layouts: mono to stereo duplicates, stereo to mono averages,
+    /// and 5.1 to stereo uses the standard down-mix coefficients. Other up-mixes copy
+    /// the matching channels and repeat the last source channel; other down-mixes
+    /// average all source channels into every target channel, without speaker weights.
+    Speakers,
+    /// Truncate extra channels or zero-pad missing ones, with no mixing.
+    Discrete,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelConfig {
+    pub count: usize,
+    pub interpretation: ChannelInterpretation,
+}
+
+impl ChannelConfig {
+    pub fn new(count: usize, interpretation: ChannelInterpretation) -> Self {
+        ChannelConfig {
+            count,
+            interpretation,
+        }
+    }
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        ChannelConfig::new(1, ChannelInterpretation::Speakers)
+    }
+}
+
+/// Mix `source` to `config.count` channels, following `config.interpretation`.
+fn mix_to<const N: usize>(source: &Buffer<N>, config: ChannelConfig) -> Buffer<N> {
+    if source.channel_count() == config.count {
+        return source.clone();
+    }
+
+    match config.interpretation {
+        ChannelInterpretation::Discrete => discrete_mix(source, config.count),
+        ChannelInterpretation::Speakers => speaker_mix(source, config.count),
+    }
+}
+
+fn discrete_mix<const N: usize>(source: &Buffer<N>, target: usize) -> Buffer<N> {
+    let mut mixed = Buffer::new(target);
+    for ch in 0..target.min(source.channel_count()) {
+        mixed.channel_mut(ch).copy_from_slice(source.channel(ch));
+    }
+    mixed
+}
+
+fn speaker_mix<const N: usize>(source: &Buffer<N>, target: usize) -> Buffer<N> {
+    let mut mixed = Buffer::new(target);
+    match (source.channel_count(), target) {
+        (1, 2) => {
+            mixed.channel_mut(0).copy_from_slice(source.channel(0));
+            mixed.channel_mut(1).copy_from_slice(source.channel(0));
+        }
+        (2, 1) => {
+            for i in 0..N {
+                mixed.channel_mut(0)[i] = 0.5 * (source.channel(0)[i] + source.channel(1)[i]);
+            }
+        }
+        (6, 2) => {
+            // 5.1 (FL, FR, C, LFE, SL, SR) down-mixed to stereo.
+            for i in 0..N {
+                let (fl, fr, c, sl, sr) = (
+                    source.channel(0)[i],
+                    source.channel(1)[i],
+                    source.channel(2)[i],
+                    source.channel(4)[i],
+                    source.channel(5)[i],
+                );
+                mixed.channel_mut(0)[i] = fl + 0.707 * (c + sl);
+                mixed.channel_mut(1)[i] = fr + 0.707 * (c + sr);
+            }
+        }
+        (from, to) if from < to => {
+            // Generic up-mix: duplicate the last source channel into the rest.
+            for ch in 0..to {
+                mixed
+                    .channel_mut(ch)
+                    .copy_from_slice(source.channel(ch.min(from - 1)));
+            }
+        }
+        (from, to) => {
+            // Generic down-mix: average all source channels, then write that average
+            // into every target channel so none of them are left silent.
+            for i in 0..N {
+                let sum: f32 = (0..from).map(|ch| source.channel(ch)[i]).sum();
+                let avg = sum / from as f32;
+                for ch in 0..to {
+                    mixed.channel_mut(ch)[i] = avg;
+                }
+            }
+        }
+    }
+    mixed
+}
+
+/// The edge weight connecting one node's output port to another node's input port,
+/// following the `self_index`/`other_index` port model used by web-audio-api.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Port {
+    pub from_output: usize,
+    pub to_input: usize,
+}
+
+/// A live parameter update for a running node, addressed by a per-node parameter
+/// `index` so a node with several parameters can tell them apart.
+pub enum Message {
+    SetToFloat { index: usize, value: f32 },
+}
+
+pub struct NodeData<T, const N: usize> {
+    pub buffer: Buffer<N>,
+    pub channel_config: ChannelConfig,
+    pending: Vec<Message>,
+    /// Set from the `NodeStatus` the node's last `process` call returned.
+    pub finished: bool,
     pub node: T,
 }
 
-impl<T> NodeData<T> {
+impl<T, const N: usize> NodeData<T, N> {
     pub fn new(node: T) -> Self {
-        NodeData { node, buffer: 0 }
+        Self::with_channel_config(node, ChannelConfig::default())
+    }
+
+    pub fn with_channel_config(node: T, channel_config: ChannelConfig) -> Self {
+        NodeData {
+            node,
+            buffer: Buffer::new(channel_config.count),
+            channel_config,
+            pending: Vec::new(),
+            finished: false,
+        }
     }
 }
 
-pub struct Input {
-    pub node_id: usize,
-    data: i32,
+pub struct Input<const N: usize> {
+    pub to_input: usize,
+    data: Buffer<N>,
 }
 
-impl Input {
-    fn new(node_id: usize, data: i32) -> Self {
-        Input { node_id, data }
+impl<const N: usize> Input<N> {
+    fn new(to_input: usize, data: Buffer<N>) -> Self {
+        Input { to_input, data }
     }
 }
 
-pub trait Node {
-    fn process(&mut self, inputs: &HashMap<usize, Input>, output: &mut i32);
+/// Context passed to every `Node::process` call so time-based nodes (oscillators,
+/// envelopes) can compute phase increments without the `AudioContext` itself.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessContext {
+    pub sample_rate: usize,
+    pub channels: usize,
+    pub block_size: usize,
+}
+
+/// Whether a node still has work to do. A `Finished` node is freed once it's no
+/// longer reachable from the destination, so one-shot nodes (envelopes, sample
+/// players) can clean themselves up instead of accumulating forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeStatus {
+    Continue,
+    Finished,
+}
+
+pub trait Node<const N: usize> {
+    fn process(
+        &mut self,
+        ctx: &ProcessContext,
+        inputs: &HashMap<usize, Input<N>>,
+        output: &mut Buffer<N>,
+    ) -> NodeStatus;
+
+    /// Handle a live parameter update sent via `AudioContext::send`. The default does
+    /// nothing, so only nodes with controllable parameters need to override it.
+    fn recv(&mut self, _msg: Message) {}
 }
 
-pub struct BoxedNode(pub Box<dyn Node>);
+pub struct BoxedNode<const N: usize>(pub Box<dyn Node<N>>);
 
-impl BoxedNode {
-    pub fn new(node: impl Node + 'static) -> Self {
+impl<const N: usize> BoxedNode<N> {
+    pub fn new(node: impl Node<N> + 'static) -> Self {
         BoxedNode(Box::new(node))
     }
 }
 
-impl Deref for BoxedNode {
-    type Target = Box<dyn Node>;
+impl<const N: usize> Deref for BoxedNode<N> {
+    type Target = Box<dyn Node<N>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
-impl DerefMut for BoxedNode {
+impl<const N: usize> DerefMut for BoxedNode<N> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
 
-pub struct Processor<G: Visitable> {
+pub struct Processor<G: Visitable, const N: usize> {
     dfs_post_order: DfsPostOrder<G::NodeId, G::Map>,
-    inputs: HashMap<usize, Input>,
+    inputs: HashMap<usize, Input<N>>,
+    /// The cached processing order, valid as long as `dirty` is `false`.
+    order: Vec<G::NodeId>,
+    /// Set by the owning `AudioContext` whenever the graph's nodes/edges change.
+    dirty: bool,
+    /// Whether the last rebuild found a feedback loop. A cycle isn't an error here:
+    /// because each node's buffer holds last block's output until it's processed
+    /// again, a node that feeds back into one of its own ancestors is naturally read
+    /// one block late, rather than recursing.
+    has_cycle: bool,
+    context: ProcessContext,
+    /// Finished nodes no longer reachable from the destination, found by the last
+    /// `process` call and awaiting removal by the owning `AudioContext`.
+    finished_unreachable: Vec<G::NodeId>,
 }
 
-impl<G> Processor<G>
+impl<G, const N: usize> Processor<G, N>
 where
     G: Visitable + NodeIndexable,
 {
-    pub fn new() -> Self
+    pub fn new(context: ProcessContext) -> Self
     where
         G::Map: Default,
     {
-        let mut dfs_post_order = DfsPostOrder::default();
-        dfs_post_order.stack = Vec::new();
-        let inputs = HashMap::new();
         Self {
-            dfs_post_order,
-            inputs,
+            dfs_post_order: DfsPostOrder::default(),
+            inputs: HashMap::new(),
+            order: Vec::new(),
+            dirty: true,
+            has_cycle: false,
+            context,
+            finished_unreachable: Vec::new(),
         }
     }
 
+    /// Marks the cached processing order stale; call whenever the graph's nodes or
+    /// edges change. The order is rebuilt lazily, on the next `process` call.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn has_cycle(&self) -> bool {
+        self.has_cycle
+    }
+
+    /// Returns (and clears) the finished, unreachable nodes found by the last
+    /// `process` call, for the caller to remove from the graph.
+    pub fn take_finished(&mut self) -> Vec<G::NodeId> {
+        std::mem::take(&mut self.finished_unreachable)
+    }
+
     pub fn process(&mut self, graph: &mut G, node: G::NodeId)
     where
-        G: Visitable + DataMapMut + Data<NodeWeight = AudioNodeData>,
-        for<'a> &'a G: GraphBase<NodeId = G::NodeId> + IntoNeighborsDirected,
+        G: Visitable + DataMapMut + Data<NodeWeight = AudioNodeData<N>, EdgeWeight = Port>,
+        G::NodeId: Copy + std::hash::Hash + Eq,
+        for<'a> &'a G: GraphBase<NodeId = G::NodeId>
+            + IntoEdgesDirected
+            + IntoNeighbors
+            + IntoNodeIdentifiers
+            + Data<EdgeWeight = Port>,
     {
         const NO_NODE: &str = "No node exists with the given index";
-        self.dfs_post_order.reset(Reversed(&*graph));
-        self.dfs_post_order.move_to(node);
-        while let Some(n) = self.dfs_post_order.next(Reversed(&*graph)) {
+
+        if self.dirty {
+            self.has_cycle = is_cyclic_directed(&*graph);
+            self.dfs_post_order.reset(Reversed(&*graph));
+            self.dfs_post_order.move_to(node);
+            self.order.clear();
+            while let Some(n) = self.dfs_post_order.next(Reversed(&*graph)) {
+                self.order.push(n);
+            }
+            self.dirty = false;
+        }
+
+        for &n in &self.order {
             self.inputs.clear();
-            for in_n in graph.neighbors_directed(n, Incoming) {
+            let target_config = graph.node_weight(n).expect(NO_NODE).channel_config;
+            for edge in graph.edges_directed(n, Incoming) {
+                let in_n = edge.source();
                 if n == in_n {
                     continue;
                 }
 
-                let node_id: G::NodeId = in_n;
-                let node_index = graph.to_index(node_id);
-                let in_node_data: &AudioNodeData = graph.node_weight(node_id).expect(NO_NODE);
-                let input = Input::new(node_index, in_node_data.buffer);
-                self.inputs.insert(node_index, input);
+                let port = *edge.weight();
+                let in_node_data: &AudioNodeData<N> = graph.node_weight(in_n).expect(NO_NODE);
+                let mixed = mix_to(&in_node_data.buffer, target_config);
+                match self.inputs.get_mut(&port.to_input) {
+                    Some(existing) => existing.data.add_assign(&mixed),
+                    None => {
+                        self.inputs
+                            .insert(port.to_input, Input::new(port.to_input, mixed));
+                    }
+                }
+            }
+
+            let data: &mut AudioNodeData<N> = graph.node_weight_mut(n).expect(NO_NODE);
+            for msg in data.pending.drain(..) {
+                data.node.recv(msg);
             }
+            let status = data
+                .node
+                .process(&self.context, &self.inputs, &mut data.buffer);
+            data.finished = status == NodeStatus::Finished;
+        }
 
-            let data: &mut AudioNodeData = graph.node_weight_mut(n).expect(NO_NODE);
-            data.node.process(&self.inputs, &mut data.buffer);
+        let reachable: HashSet<G::NodeId> = self.order.iter().copied().collect();
+        self.finished_unreachable.clear();
+        for id in (&*graph).node_identifiers() {
+            if !reachable.contains(&id) && graph.node_weight(id).expect(NO_NODE).finished {
+                self.finished_unreachable.push(id);
+            }
         }
     }
 }
 
-pub type AudioNodeData = NodeData<BoxedNode>;
-pub type AudioGraph = StableGraph<AudioNodeData, ()>;
+pub type AudioNodeData<const N: usize> = NodeData<BoxedNode<N>, N>;
+pub type AudioGraph<const N: usize> = StableGraph<AudioNodeData<N>, Port>;
 
-pub struct AudioContext {
-    pub graph: AudioGraph,
+pub struct AudioContext<const N: usize> {
+    pub graph: AudioGraph<N>,
     pub destination: NodeIndex,
     pub input: NodeIndex,
-    pub processor: Processor<AudioGraph>,
+    pub processor: Processor<AudioGraph<N>, N>,
+    pub sample_rate: usize,
+    pub channels: usize,
+}
+
+impl<const N: usize> AudioContext<N> {
+    pub fn add_node(&mut self, node: impl Node<N> + 'static) -> NodeIndex {
+        self.processor.mark_dirty();
+        let channel_config = ChannelConfig::new(self.channels, ChannelInterpretation::Speakers);
+        self.graph.add_node(NodeData::with_channel_config(
+            BoxedNode::new(node),
+            channel_config,
+        ))
+    }
+
+    /// Mixing of mismatched channel counts happens automatically in `Processor::process`,
+    /// driven by each node's `ChannelConfig` — no adapter node is needed here.
+    /// Connects `from`'s output 0 to `to`'s input 0; use `connect_ports` to choose the
+    /// input port on `to`.
+    pub fn connect(&mut self, from: NodeIndex, to: NodeIndex) -> EdgeIndex {
+        self.processor.mark_dirty();
+        self.graph.add_edge(from, to, Port::default())
+    }
+
+    /// Connects output `out_idx` of `from` to input `in_idx` of `to`, so `to` can tell
+    /// this connection apart from its other inputs. Multiple sources connected to the
+    /// same `in_idx` are summed before `to` sees them. Each node has a single output
+    /// buffer, so `out_idx` is stored on the edge but every output index reads it.
+    pub fn connect_ports(
+        &mut self,
+        from: NodeIndex,
+        out_idx: usize,
+        to: NodeIndex,
+        in_idx: usize,
+    ) -> EdgeIndex {
+        self.processor.mark_dirty();
+        self.graph.add_edge(
+            from,
+            to,
+            Port {
+                from_output: out_idx,
+                to_input: in_idx,
+            },
+        )
+    }
+
+    /// Whether the graph contained a feedback loop anywhere (not only among nodes that
+    /// reach the destination) when the processing order was last rebuilt. Feedback
+    /// edges are not an error: they're read with a one-block delay (see `Processor`).
+    pub fn has_feedback(&self) -> bool {
+        self.processor.has_cycle()
+    }
+
+    /// Removes a connection between two nodes.
+    pub fn disconnect(&mut self, edge: EdgeIndex) {
+        self.processor.mark_dirty();
+        self.graph.remove_edge(edge);
+    }
+
+    /// Removes `node` and its edges from the graph, e.g. once a one-shot node has
+    /// finished and is no longer needed. Prefer letting finished nodes clean
+    /// themselves up via `NodeStatus::Finished` where possible; use this to force
+    /// removal of a node that's still connected.
+    pub fn remove_node(&mut self, node: NodeIndex) {
+        self.processor.mark_dirty();
+        self.graph.remove_node(node);
+    }
+
+    /// Reconfigures `node`'s channel count, resizing (and silencing) its buffer.
+    pub fn set_channel_count(&mut self, node: NodeIndex, count: usize) {
+        let data = &mut self.graph[node];
+        data.channel_config.count = count;
+        data.buffer = Buffer::new(count);
+    }
+
+    pub fn set_channel_interpretation(
+        &mut self,
+        node: NodeIndex,
+        interpretation: ChannelInterpretation,
+    ) {
+        self.graph[node].channel_config.interpretation = interpretation;
+    }
+
+    /// Queues `msg` for `node`; it's delivered to `Node::recv` right before `node`
+    /// next processes a block.
+    pub fn send(&mut self, node: NodeIndex, msg: Message) {
+        self.graph[node].pending.push(msg);
+    }
+
+    pub fn next_block(&mut self) -> Buffer<N> {
+        self.processor.process(&mut self.graph, self.destination);
+        for finished in self.processor.take_finished() {
+            self.processor.mark_dirty();
+            self.graph.remove_node(finished);
+        }
+        self.graph[self.destination].buffer.clone()
+    }
 }
 
-impl AudioContext {
+/// Builds an `AudioContext` with an explicit sample rate, channel count, and
+/// preallocated graph capacity, matching the `AudioContextBuilder` pattern in
+/// `glicol_synth`.
+pub struct AudioContextBuilder<const N: usize> {
+    sample_rate: usize,
+    channels: usize,
+    max_nodes: usize,
+    max_edges: usize,
+}
+
+impl<const N: usize> AudioContextBuilder<N> {
     pub fn new() -> Self {
-        let mut graph = AudioGraph::new();
-        let destination = graph.add_node(AudioNodeData::new(BoxedNode::new(Sum2)));
-        let input = graph.add_node(AudioNodeData::new(BoxedNode::new(Pass)));
-        let processor = Processor::new();
+        AudioContextBuilder {
+            sample_rate: 44100,
+            channels: 2,
+            max_nodes: 0,
+            max_edges: 0,
+        }
+    }
+
+    pub fn sample_rate(mut self, sample_rate: usize) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn channels(mut self, channels: usize) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    pub fn max_edges(mut self, max_edges: usize) -> Self {
+        self.max_edges = max_edges;
+        self
+    }
+
+    pub fn build(self) -> AudioContext<N> {
+        let mut graph = AudioGraph::with_capacity(self.max_nodes, self.max_edges);
+        let channel_config = ChannelConfig::new(self.channels, ChannelInterpretation::Speakers);
+        let destination = graph.add_node(AudioNodeData::with_channel_config(
+            BoxedNode::new(Sum2),
+            channel_config,
+        ));
+        let input = graph.add_node(AudioNodeData::with_channel_config(
+            BoxedNode::new(Pass),
+            channel_config,
+        ));
+        let processor = Processor::new(ProcessContext {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            block_size: N,
+        });
         AudioContext {
             graph,
             input,
             destination,
             processor,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
         }
     }
+}
 
-    pub fn add_node(&mut self, node: impl Node + 'static) -> NodeIndex {
-        self.graph.add_node(NodeData::new(BoxedNode::new(node)))
-    }
-
-    pub fn connect(&mut self, from: NodeIndex, to: NodeIndex) -> EdgeIndex {
-        self.graph.add_edge(from, to, ())
-    }
-
-    pub fn next_block(&mut self) -> i32 {
-        self.processor.process(&mut self.graph, self.destination);
-        self.graph[self.destination].buffer
+impl<const N: usize> Default for AudioContextBuilder<N> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 pub struct Sum2;
 
-impl Node for Sum2 {
-    fn process(&mut self, inputs: &HashMap<usize, Input>, output: &mut i32) {
-        *output = inputs
-            .values()
-            .map(|input| input.data)
-            .reduce(|acc, v| acc + v)
-            .unwrap();
+impl<const N: usize> Node<N> for Sum2 {
+    fn process(
+        &mut self,
+        _ctx: &ProcessContext,
+        inputs: &HashMap<usize, Input<N>>,
+        output: &mut Buffer<N>,
+    ) -> NodeStatus {
+        *output = Buffer::new(output.channel_count());
+        for input in inputs.values() {
+            for ch in 0..output.channel_count() {
+                for (o, i) in output
+                    .channel_mut(ch)
+                    .iter_mut()
+                    .zip(input.data.channel(ch).iter())
+                {
+                    *o += i;
+                }
+            }
+        }
+        NodeStatus::Continue
     }
 }
 
 pub struct Pass;
 
-impl Node for Pass {
-    fn process(&mut self, inputs: &HashMap<usize, Input>, output: &mut i32) {
+impl<const N: usize> Node<N> for Pass {
+    fn process(
+        &mut self,
+        _ctx: &ProcessContext,
+        inputs: &HashMap<usize, Input<N>>,
+        output: &mut Buffer<N>,
+    ) -> NodeStatus {
         let input = match inputs.values().next() {
-            Some(input) => input.data,
-            None => return,
+            Some(input) => input,
+            None => return NodeStatus::Continue,
         };
-        *output = input;
+        for ch in 0..output.channel_count() {
+            output
+                .channel_mut(ch)
+                .copy_from_slice(input.data.channel(ch));
+        }
+        NodeStatus::Continue
     }
 }
 
 pub struct ConstSig {
-    value: i32,
+    value: f32,
 }
 
 impl ConstSig {
-    pub fn new(value: i32) -> Self {
+    pub fn new(value: f32) -> Self {
         ConstSig { value }
     }
 }
 
-impl Node for ConstSig {
-    fn process(&mut self, _inputs: &HashMap<usize, Input>, output: &mut i32) {
-        *output = self.value
+impl<const N: usize> Node<N> for ConstSig {
+    fn process(
+        &mut self,
+        _ctx: &ProcessContext,
+        _inputs: &HashMap<usize, Input<N>>,
+        output: &mut Buffer<N>,
+    ) -> NodeStatus {
+        for ch in 0..output.channel_count() {
+            output
+                .channel_mut(ch)
+                .iter_mut()
+                .for_each(|s| *s = self.value);
+        }
+        NodeStatus::Continue
     }
-}
 
-pub struct ConstSig {
-    val: f32,
-}
-
-impl ConstSig {
-    pub fn new(val: f32) -> Self {
-        ConstSig { val }
-    }
-}
-
-impl Into<BoxedNode> for ConstSig {
-    fn into(self) -> BoxedNode {
-        BoxedNode::new(self)
+    fn recv(&mut self, msg: Message) {
+        if let Message::SetToFloat { index: 0, value } = msg {
+            self.value = value;
+        }
     }
 }
 
 pub fn test_audio_context() {
-    let mut context = AudioContext::new();
-    let const_sig_10 = context.add_node(ConstSig::new(10));
-    let const_sig_43 = context.add_node(ConstSig::new(43));
+    let mut context = AudioContextBuilder::<128>::new()
+        .sample_rate(44100)
+        .channels(1)
+        .max_nodes(16)
+        .max_edges(16)
+        .build();
+    let const_sig_10 = context.add_node(ConstSig::new(10.0));
+    let const_sig_43 = context.add_node(ConstSig::new(43.0));
     let pass_node = context.add_node(Pass);
     let sum_node = context.add_node(Sum2);
     context.connect(const_sig_10, pass_node);
@@ -213,3 +667,188 @@ pub fn test_audio_context() {
     context.connect(sum_node, context.destination);
     println!("dest block {:?}", context.next_block());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speaker_mix_down_mix_fills_every_target_channel() {
+        let mut source = Buffer::<4>::new(4);
+        for ch in 0..4 {
+            source.channel_mut(ch).iter_mut().for_each(|s| *s = 1.0);
+        }
+
+        let mixed = mix_to(
+            &source,
+            ChannelConfig::new(2, ChannelInterpretation::Speakers),
+        );
+
+        assert_eq!(mixed.channel(0), &[1.0; 4]);
+        assert_eq!(mixed.channel(1), &[1.0; 4]);
+    }
+
+    struct PortProbe;
+
+    impl<const N: usize> Node<N> for PortProbe {
+        fn process(
+            &mut self,
+            _ctx: &ProcessContext,
+            inputs: &HashMap<usize, Input<N>>,
+            output: &mut Buffer<N>,
+        ) -> NodeStatus {
+            let signal = inputs.get(&0).map_or(0.0, |i| i.data.channel(0)[0]);
+            let cutoff = inputs.get(&1).map_or(0.0, |i| i.data.channel(0)[0]);
+            output.channel_mut(0)[0] = signal - cutoff;
+            NodeStatus::Continue
+        }
+    }
+
+    #[test]
+    fn connect_ports_keeps_inputs_addressed_by_port() {
+        let mut context = AudioContextBuilder::<4>::new()
+            .channels(1)
+            .max_nodes(8)
+            .max_edges(8)
+            .build();
+        let signal = context.add_node(ConstSig::new(7.0));
+        let cutoff = context.add_node(ConstSig::new(2.0));
+        let probe = context.add_node(PortProbe);
+        context.connect_ports(signal, 0, probe, 0);
+        context.connect_ports(cutoff, 0, probe, 1);
+        context.connect(probe, context.destination);
+
+        // If the two sources were conflated onto the same input port rather than
+        // kept apart by `to_input`, this wouldn't come out to signal - cutoff.
+        assert_eq!(context.next_block().channel(0)[0], 5.0);
+    }
+
+    #[test]
+    fn send_updates_const_sig_before_the_next_block() {
+        let mut context = AudioContextBuilder::<4>::new()
+            .channels(1)
+            .max_nodes(8)
+            .max_edges(8)
+            .build();
+        let const_sig = context.add_node(ConstSig::new(1.0));
+        context.connect(const_sig, context.destination);
+        assert_eq!(context.next_block().channel(0), &[1.0; 4]);
+
+        context.send(
+            const_sig,
+            Message::SetToFloat {
+                index: 0,
+                value: 5.0,
+            },
+        );
+        assert_eq!(context.next_block().channel(0), &[5.0; 4]);
+    }
+
+    struct ContextProbe;
+
+    impl<const N: usize> Node<N> for ContextProbe {
+        fn process(
+            &mut self,
+            ctx: &ProcessContext,
+            _inputs: &HashMap<usize, Input<N>>,
+            output: &mut Buffer<N>,
+        ) -> NodeStatus {
+            output.channel_mut(0)[0] = ctx.sample_rate as f32;
+            output.channel_mut(0)[1] = ctx.block_size as f32;
+            NodeStatus::Continue
+        }
+    }
+
+    #[test]
+    fn builder_settings_reach_the_process_context() {
+        let mut context = AudioContextBuilder::<4>::new()
+            .sample_rate(48000)
+            .channels(1)
+            .max_nodes(8)
+            .max_edges(8)
+            .build();
+        let probe = context.add_node(ContextProbe);
+        context.connect(probe, context.destination);
+
+        let block = context.next_block();
+        assert_eq!(block.channel(0)[0], 48000.0);
+        assert_eq!(block.channel(0)[1], 4.0);
+    }
+
+    #[test]
+    fn cached_order_is_rebuilt_after_connecting_a_new_node() {
+        let mut context = AudioContextBuilder::<4>::new()
+            .channels(1)
+            .max_nodes(8)
+            .max_edges(8)
+            .build();
+        let a = context.add_node(ConstSig::new(1.0));
+        context.connect(a, context.destination);
+        assert_eq!(context.next_block().channel(0), &[1.0; 4]);
+
+        // Adding and wiring in a second node must mark the cached order dirty,
+        // or this block would still reflect only `a`.
+        let b = context.add_node(ConstSig::new(2.0));
+        context.connect(b, context.destination);
+        assert_eq!(context.next_block().channel(0), &[3.0; 4]);
+    }
+
+    #[test]
+    fn has_feedback_detects_cycles_without_hanging() {
+        let mut context = AudioContextBuilder::<4>::new()
+            .channels(1)
+            .max_nodes(8)
+            .max_edges(8)
+            .build();
+        let const_sig = context.add_node(ConstSig::new(1.0));
+        let pass_node = context.add_node(Pass);
+        context.connect(const_sig, pass_node);
+        context.connect(pass_node, context.destination);
+        // Feed the destination's own output back into `pass_node`, forming a cycle.
+        context.connect(context.destination, pass_node);
+
+        // A node in a feedback loop reads its ancestors' *previous* block rather
+        // than recursing, so each block must come out finite (here it grows by one
+        // per block), not hang or NaN.
+        for _ in 0..3 {
+            let block = context.next_block();
+            assert!(block.channel(0).iter().all(|s| s.is_finite()));
+        }
+        assert!(context.has_feedback());
+    }
+
+    struct OneShot;
+
+    impl<const N: usize> Node<N> for OneShot {
+        fn process(
+            &mut self,
+            _ctx: &ProcessContext,
+            _inputs: &HashMap<usize, Input<N>>,
+            output: &mut Buffer<N>,
+        ) -> NodeStatus {
+            output.channel_mut(0).iter_mut().for_each(|s| *s = 1.0);
+            NodeStatus::Finished
+        }
+    }
+
+    #[test]
+    fn finished_nodes_are_freed_once_unreachable() {
+        let mut context = AudioContextBuilder::<4>::new()
+            .channels(1)
+            .max_nodes(8)
+            .max_edges(8)
+            .build();
+        let one_shot = context.add_node(OneShot);
+        let edge = context.connect(one_shot, context.destination);
+
+        // Still reachable from the destination, so it stays around even though
+        // it just reported itself finished.
+        context.next_block();
+        assert!(context.graph.contains_node(one_shot));
+
+        // Once it's no longer reachable, the next block should sweep it away.
+        context.disconnect(edge);
+        context.next_block();
+        assert!(!context.graph.contains_node(one_shot));
+    }
+}